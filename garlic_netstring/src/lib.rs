@@ -2,11 +2,63 @@
 //!
 //! [netstring]: https://cr.yp.to/proto/netstrings.txt
 
+pub mod vlq;
+
 use std::io::{
     self,
     Read,
+    Take,
+    Write,
 };
 
+/// Encode a netstring.
+///
+/// Writes the length of `payload` in decimal,
+/// followed by a colon, `payload` itself, and a terminating comma.
+pub fn encode<W>(w: &mut W, payload: &[u8]) -> io::Result<()>
+    where W: Write + ?Sized
+{
+    write_len(w, payload.len() as u64)?;
+    w.write_all(b":")?;
+    w.write_all(payload)?;
+    w.write_all(b",")?;
+    Ok(())
+}
+
+/// Write the decimal digits of `len`, without allocating a temporary string.
+fn write_len<W>(w: &mut W, len: u64) -> io::Result<()>
+    where W: Write + ?Sized
+{
+    let mut digits = [0u8; 20];
+    let mut i = digits.len();
+    let mut n = len;
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    w.write_all(&digits[i ..])
+}
+
+/// Extension trait for writing netstrings to a [`Write`] implementation.
+pub trait WriteNetstring: Write
+{
+    /// Write `payload` as a netstring.
+    ///
+    /// See [`encode`] for details.
+    fn write_netstring(&mut self, payload: &[u8]) -> io::Result<()>
+    {
+        encode(self, payload)
+    }
+}
+
+impl<W: Write> WriteNetstring for W
+{
+}
+
 /// Decode a netstring.
 ///
 /// First, the length specified in the netstring is read.
@@ -19,14 +71,17 @@ use std::io::{
 pub fn decode<R, F>(r: &mut R, f: F, buf: &mut Vec<u8>) -> Result<u64, Error>
     where R: Read, F: FnOnce(u64) -> bool
 {
+    let mut offset = 0u64;
+
     // Read the length specified.
-    let len = decode_len(r)?;
+    let len = decode_len(r, &mut offset)?;
     if !f(len) {
         return Err(Error::Length(len));
     }
 
     // Read the payload.
     let nread = r.take(len).read_to_end(buf)?;
+    offset += nread as u64;
     if nread as u64 != len {
         return Err(Error::Incomplete);
     }
@@ -35,32 +90,218 @@ pub fn decode<R, F>(r: &mut R, f: F, buf: &mut Vec<u8>) -> Result<u64, Error>
     let mut commabuf = [0];
     r.read_exact(&mut commabuf)?;
     if commabuf[0] != b',' {
-        return Err(Error::Syntax);
+        return Err(Error::Syntax{byte: commabuf[0], offset});
     }
 
     Ok(len)
 }
 
-fn decode_len<R>(r: &mut R) -> Result<u64, Error>
+/// Decode a netstring without buffering its payload.
+///
+/// This behaves like [`decode`], except that instead of reading the payload
+/// into a `Vec<u8>`, it returns a [`NetstringPayload`] that streams the
+/// payload from `r` as it is read. This allows handling netstrings whose
+/// payload is too large to hold in memory at once.
+///
+/// The terminating comma is not read until the returned payload is dropped
+/// or [`NetstringPayload::finish`] is called explicitly; if the caller does
+/// not read the payload to completion, it is skipped over at that point.
+/// Any error in skipping the remainder or reading the comma is only
+/// reported by [`NetstringPayload::finish`]; if the payload is merely
+/// dropped, such an error is silently discarded.
+pub fn decode_reader<'a, R, F>(r: &'a mut R, f: F) -> Result<NetstringPayload<'a, R>, Error>
+    where R: Read, F: FnOnce(u64) -> bool
+{
+    let mut offset = 0u64;
+    let len = decode_len(r, &mut offset)?;
+    if !f(len) {
+        return Err(Error::Length(len));
+    }
+
+    Ok(NetstringPayload{inner: r.take(len), finished: false, offset})
+}
+
+/// A netstring payload being streamed from a reader, returned by [`decode_reader`].
+///
+/// Implements [`Read`] to yield the payload bytes.
+/// The terminating comma is consumed on [`finish`](Self::finish) or on drop.
+pub struct NetstringPayload<'a, R>
+    where R: Read
+{
+    inner: Take<&'a mut R>,
+    finished: bool,
+    offset: u64,
+}
+
+impl<'a, R: Read> NetstringPayload<'a, R>
+{
+    /// Consume any unread payload bytes and verify the terminating comma.
+    ///
+    /// This is called automatically on drop, but errors from doing so are
+    /// then discarded; call this method explicitly to observe them.
+    pub fn finish(mut self) -> Result<(), Error>
+    {
+        self.finish_impl()
+    }
+
+    /// The number of payload bytes that have not yet been read.
+    fn remaining(&self) -> u64
+    {
+        self.inner.limit()
+    }
+
+    fn finish_impl(&mut self) -> Result<(), Error>
+    {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let nskipped = io::copy(&mut self.inner, &mut io::sink())?;
+        self.offset += nskipped;
+
+        let mut commabuf = [0];
+        match self.inner.get_mut().read(&mut commabuf)? {
+            1 if commabuf[0] == b',' => Ok(()),
+            1 => Err(Error::Syntax{byte: commabuf[0], offset: self.offset}),
+            _ => Err(Error::Incomplete),
+        }
+    }
+}
+
+impl<'a, R: Read> Read for NetstringPayload<'a, R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.inner.read(buf)
+    }
+}
+
+impl<'a, R: Read> Drop for NetstringPayload<'a, R>
+{
+    fn drop(&mut self)
+    {
+        let _ = self.finish_impl();
+    }
+}
+
+fn decode_len<R>(r: &mut R, offset: &mut u64) -> Result<u64, Error>
     where R: Read
 {
     let mut len = 0u64;
     let mut buf = [0];
     loop {
         r.read_exact(&mut buf)?;
-        match buf[0] {
+        let byte = buf[0];
+        let pos = *offset;
+        *offset += 1;
+        match byte {
             b'0' ..= b'9' => {
-                let digit = buf[0] - b'0';
-                len = len.checked_mul(10).ok_or(Error::Overflow)?;
-                len = len.checked_add(digit as u64).ok_or(Error::Overflow)?;
+                let digit = byte - b'0';
+                len = len.checked_mul(10).ok_or(Error::Overflow{byte, offset: pos})?;
+                len = len.checked_add(digit as u64).ok_or(Error::Overflow{byte, offset: pos})?;
             },
             b':' => return Ok(len),
-            _ => return Err(Error::Syntax),
+            _ => return Err(Error::Syntax{byte, offset: pos}),
+        }
+    }
+}
+
+/// Options for [`decode_strict`].
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeOptions
+{
+    /// The maximum number of decimal digits accepted in the length prefix.
+    pub max_len_digits: u64,
+}
+
+impl Default for DecodeOptions
+{
+    fn default() -> Self
+    {
+        DecodeOptions{max_len_digits: 19}
+    }
+}
+
+/// Decode a netstring, like [`decode`], but require a canonical length prefix.
+///
+/// A length prefix is canonical if it consists of at least one digit,
+/// has no leading `'0'` unless it is exactly `"0"`,
+/// and does not exceed `options.max_len_digits` digits.
+/// Any other length prefix is rejected with [`Error::NonCanonical`],
+/// rather than being parsed leniently or looping over an unbounded number
+/// of digits.
+pub fn decode_strict<R, F>(
+    r: &mut R,
+    f: F,
+    buf: &mut Vec<u8>,
+    options: DecodeOptions,
+) -> Result<u64, Error>
+    where R: Read, F: FnOnce(u64) -> bool
+{
+    let mut offset = 0u64;
+
+    // Read the length specified.
+    let len = decode_len_strict(r, options, &mut offset)?;
+    if !f(len) {
+        return Err(Error::Length(len));
+    }
+
+    // Read the payload.
+    let nread = r.take(len).read_to_end(buf)?;
+    offset += nread as u64;
+    if nread as u64 != len {
+        return Err(Error::Incomplete);
+    }
+
+    // Read the terminating comma.
+    let mut commabuf = [0];
+    r.read_exact(&mut commabuf)?;
+    if commabuf[0] != b',' {
+        return Err(Error::Syntax{byte: commabuf[0], offset});
+    }
+
+    Ok(len)
+}
+
+fn decode_len_strict<R>(r: &mut R, options: DecodeOptions, offset: &mut u64) -> Result<u64, Error>
+    where R: Read
+{
+    let mut len = 0u64;
+    let mut ndigits = 0u64;
+    let mut buf = [0];
+    loop {
+        r.read_exact(&mut buf)?;
+        let byte = buf[0];
+        let pos = *offset;
+        *offset += 1;
+        match byte {
+            b'0' if ndigits == 0 => {
+                // A leading zero is only canonical if the entire length is "0".
+                r.read_exact(&mut buf)?;
+                *offset += 1;
+                return match buf[0] {
+                    b':' => Ok(0),
+                    _ => Err(Error::NonCanonical),
+                };
+            },
+            b'0' ..= b'9' => {
+                ndigits += 1;
+                if ndigits > options.max_len_digits {
+                    return Err(Error::NonCanonical);
+                }
+                let digit = byte - b'0';
+                len = len.checked_mul(10).ok_or(Error::Overflow{byte, offset: pos})?;
+                len = len.checked_add(digit as u64).ok_or(Error::Overflow{byte, offset: pos})?;
+            },
+            b':' if ndigits > 0 => return Ok(len),
+            _ => return Err(Error::NonCanonical),
         }
     }
 }
 
 /// Error related to decoding a netstring.
+#[derive(Debug)]
 pub enum Error
 {
     /// The `Read` impl returned an error.
@@ -76,11 +317,21 @@ pub enum Error
 
     /// The length specified in the netstring
     /// would overflow a `u64`.
-    Overflow,
+    /// The offending byte (a decimal digit, or a length byte in the [`vlq`] format)
+    /// and the byte offset at which it occurred are given.
+    Overflow{byte: u8, offset: u64},
 
     /// The netstring could not be parsed
     /// because the semicolon or comma was missing.
-    Syntax,
+    /// The unexpected byte and the byte offset at which it occurred are given.
+    Syntax{byte: u8, offset: u64},
+
+    /// The length prefix was rejected by [`decode_strict`]
+    /// for not being in canonical form: it had no digit before the colon,
+    /// a leading `'0'` despite not being exactly `"0"`,
+    /// more digits than the configured maximum,
+    /// or a byte that is neither a digit nor the colon.
+    NonCanonical,
 }
 
 impl From<io::Error> for Error
@@ -91,6 +342,154 @@ impl From<io::Error> for Error
     }
 }
 
+/// Render a byte for an error message: as a quoted `char` if printable ASCII,
+/// or as a hex literal otherwise (the VLQ format uses arbitrary binary bytes,
+/// which would otherwise render as raw control characters).
+fn describe_byte(byte: u8) -> String
+{
+    if byte.is_ascii_graphic() || byte == b' ' {
+        format!("{:?}", byte as char)
+    } else {
+        format!("{:#04x}", byte)
+    }
+}
+
+impl std::fmt::Display for Error
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self {
+            Error::Io(e) =>
+                write!(f, "I/O error: {}", e),
+            Error::Incomplete =>
+                write!(f, "netstring payload ended before the specified length"),
+            Error::Length(len) =>
+                write!(f, "netstring length {} rejected by validation predicate", len),
+            Error::Overflow{byte, offset} =>
+                write!(f, "netstring length overflowed at byte {}, offset {}", describe_byte(*byte), offset),
+            Error::Syntax{byte, offset} =>
+                write!(f, "unexpected byte {} at offset {} while parsing netstring", describe_byte(*byte), offset),
+            Error::NonCanonical =>
+                write!(f, "netstring length prefix was not in canonical form"),
+        }
+    }
+}
+
+impl std::error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A value that can be serialized to, and deserialized from,
+/// a single self-describing netstring.
+///
+/// Composite implementations (such as the one for `Vec<T>`) nest each of
+/// their members inside its own netstring frame, so the outer netstring's
+/// payload is the concatenation of the inner frames. This makes the format
+/// self-describing: a `WireFormat` value can always be skipped over without
+/// knowing its type, by reading just one netstring.
+pub trait WireFormat: Sized
+{
+    /// The number of bytes occupied by this value's netstring payload,
+    /// i.e. the length that [`encode`](Self::encode) places in the length prefix.
+    fn byte_size(&self) -> u64;
+
+    /// Encode this value as a netstring.
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+
+    /// Decode a value previously written by [`encode`](Self::encode).
+    fn decode<R: Read>(r: &mut R) -> Result<Self, Error>;
+}
+
+/// The number of decimal digits in the representation of `n`.
+fn digit_count(mut n: u64) -> u64
+{
+    let mut count = 1;
+    while n >= 10 {
+        n /= 10;
+        count += 1;
+    }
+    count
+}
+
+/// The total number of bytes occupied by a netstring framing a payload of `payload_len` bytes.
+fn frame_size(payload_len: u64) -> u64
+{
+    digit_count(payload_len) + 2 + payload_len
+}
+
+macro_rules! impl_wireformat_for_uint {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl WireFormat for $t
+            {
+                fn byte_size(&self) -> u64
+                {
+                    std::mem::size_of::<$t>() as u64
+                }
+
+                fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>
+                {
+                    encode(w, &self.to_be_bytes())
+                }
+
+                fn decode<R: Read>(r: &mut R) -> Result<Self, Error>
+                {
+                    const SIZE: u64 = std::mem::size_of::<$t>() as u64;
+                    let mut buf = Vec::new();
+                    decode(r, |len| len == SIZE, &mut buf)?;
+                    let mut array = [0; std::mem::size_of::<$t>()];
+                    array.copy_from_slice(&buf);
+                    Ok(<$t>::from_be_bytes(array))
+                }
+            }
+        )*
+    };
+}
+
+impl_wireformat_for_uint!(u8, u16, u32, u64);
+
+impl<T: WireFormat> WireFormat for Vec<T>
+{
+    fn byte_size(&self) -> u64
+    {
+        self.iter().map(|item| frame_size(item.byte_size())).sum()
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>
+    {
+        write_len(w, self.byte_size())?;
+        w.write_all(b":")?;
+        for item in self {
+            item.encode(w)?;
+        }
+        w.write_all(b",")?;
+        Ok(())
+    }
+
+    fn decode<R: Read>(r: &mut R) -> Result<Self, Error>
+    {
+        let mut payload = decode_reader(r, |_| true)?;
+
+        // Stop only on exact, clean exhaustion of the outer payload; any
+        // error while bytes still remain (e.g. a truncated trailing item)
+        // must propagate instead of being mistaken for end-of-items.
+        let mut items = Vec::new();
+        while payload.remaining() > 0 {
+            items.push(T::decode(&mut payload)?);
+        }
+
+        payload.finish()?;
+        Ok(items)
+    }
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -99,10 +498,14 @@ mod tests
         std::io::Cursor,
     };
 
+    /// An input, and the expected payload and reader position after a
+    /// successful decode, or `None` if the input is expected to be rejected.
+    type Example = (&'static [u8], Option<(&'static [u8], u64)>);
+
     #[test]
     fn test_examples()
     {
-        let examples: &[(&[u8], Option<(&[u8], u64)>)] = &[
+        let examples: &[Example] = &[
 
             // Erroneous examples.
             (b"", None),
@@ -134,4 +537,193 @@ mod tests
             }
         }
     }
+
+    #[test]
+    fn test_encode()
+    {
+        let examples: &[(&[u8], &[u8])] = &[
+            (b"", b"0:,"),
+            (b"A", b"1:A,"),
+            (b"AB", b"2:AB,"),
+            (b"Hello, world!", b"13:Hello, world!,"),
+        ];
+
+        for &(payload, expected) in examples {
+            let mut actual = Vec::new();
+            encode(&mut actual, payload).unwrap();
+            assert_eq!(actual, expected);
+
+            let mut via_trait = Vec::new();
+            via_trait.write_netstring(payload).unwrap();
+            assert_eq!(via_trait, expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_reader_full_read()
+    {
+        let mut cursor = Cursor::new(b"13:Hello, world!,X".as_slice());
+        let mut payload = decode_reader(&mut cursor, |_| true).unwrap();
+        let mut actual = Vec::new();
+        payload.read_to_end(&mut actual).unwrap();
+        payload.finish().unwrap();
+        assert_eq!(actual, b"Hello, world!");
+        assert_eq!(cursor.position(), 17);
+    }
+
+    #[test]
+    fn test_decode_reader_partial_read()
+    {
+        let mut cursor = Cursor::new(b"13:Hello, world!,X".as_slice());
+        let mut payload = decode_reader(&mut cursor, |_| true).unwrap();
+        let mut actual = [0; 5];
+        payload.read_exact(&mut actual).unwrap();
+        payload.finish().unwrap();
+        assert_eq!(&actual, b"Hello");
+        assert_eq!(cursor.position(), 17);
+    }
+
+    #[test]
+    fn test_decode_reader_missing_comma()
+    {
+        let mut cursor = Cursor::new(b"1:A".as_slice());
+        let payload = decode_reader(&mut cursor, |_| true).unwrap();
+        assert!(payload.finish().is_err());
+    }
+
+    #[test]
+    fn test_decode_strict()
+    {
+        let examples: &[Example] = &[
+
+            // Rejected by strict mode, though lenient decode accepts them.
+            (b":,", None),
+            (b"01:A,", None),
+            (b"00:,", None),
+
+            // Still erroneous.
+            (b"", None),
+            (b"1:A", None),
+            (b"A:1,", None),
+
+            // Valid examples.
+            (b"0:,", Some((b"", 3))),
+            (b"1:A,", Some((b"A", 4))),
+            (b"13:Hello, world!,X", Some((b"Hello, world!", 17))),
+
+        ];
+
+        for &(input, expected) in examples {
+            let mut cursor = Cursor::new(input);
+            let mut actual = Vec::new();
+            let result = decode_strict(&mut cursor, |_| true, &mut actual, DecodeOptions::default());
+            match expected {
+                None => assert!(result.is_err()),
+                Some((expected_payload, expected_position)) => {
+                    assert_eq!(actual, expected_payload);
+                    assert_eq!(cursor.position(), expected_position);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_strict_max_len_digits()
+    {
+        let options = DecodeOptions{max_len_digits: 2};
+
+        // Exactly at the limit: the length prefix is accepted.
+        let mut input = b"42:".to_vec();
+        input.extend(std::iter::repeat_n(b'x', 42));
+        input.push(b',');
+        let mut cursor = Cursor::new(input.as_slice());
+        let mut buf = Vec::new();
+        let len = decode_strict(&mut cursor, |_| true, &mut buf, options).unwrap();
+        assert_eq!(len, 42);
+
+        // One digit over the limit: rejected before the length is even used.
+        let mut cursor = Cursor::new(b"100:...".as_slice());
+        let mut buf = Vec::new();
+        assert!(matches!(
+            decode_strict(&mut cursor, |_| true, &mut buf, options),
+            Err(Error::NonCanonical),
+        ));
+    }
+
+    #[test]
+    fn test_error_offsets()
+    {
+        let mut cursor = Cursor::new(b"1:A.".as_slice());
+        let mut buf = Vec::new();
+        let err = decode(&mut cursor, |_| true, &mut buf).unwrap_err();
+        assert!(matches!(err, Error::Syntax{byte: b'.', offset: 3}));
+
+        let mut cursor = Cursor::new(b"1?A,".as_slice());
+        let mut buf = Vec::new();
+        let err = decode(&mut cursor, |_| true, &mut buf).unwrap_err();
+        assert!(matches!(err, Error::Syntax{byte: b'?', offset: 1}));
+    }
+
+    #[test]
+    fn test_error_display()
+    {
+        let err = Error::Syntax{byte: b'?', offset: 1};
+        assert_eq!(err.to_string(), "unexpected byte '?' at offset 1 while parsing netstring");
+
+        let err: Box<dyn std::error::Error> = Box::new(Error::Incomplete);
+        assert_eq!(err.to_string(), "netstring payload ended before the specified length");
+    }
+
+    #[test]
+    fn test_error_display_non_printable_byte()
+    {
+        let err = Error::Overflow{byte: 0x80, offset: 3};
+        assert_eq!(err.to_string(), "netstring length overflowed at byte 0x80, offset 3");
+    }
+
+    #[test]
+    fn test_wireformat_uint_roundtrip()
+    {
+        let mut buf = Vec::new();
+        42u32.encode(&mut buf).unwrap();
+        assert_eq!(buf, b"4:\0\0\0\x2a,");
+
+        let mut cursor = Cursor::new(buf.as_slice());
+        assert_eq!(u32::decode(&mut cursor).unwrap(), 42u32);
+    }
+
+    #[test]
+    fn test_wireformat_vec_roundtrip()
+    {
+        let items: Vec<u16> = vec![1, 2, 300];
+
+        let mut buf = Vec::new();
+        items.encode(&mut buf).unwrap();
+        assert_eq!(buf, b"15:2:\0\x01,2:\0\x02,2:\x01\x2c,,");
+
+        let mut cursor = Cursor::new(buf.as_slice());
+        assert_eq!(Vec::<u16>::decode(&mut cursor).unwrap(), items);
+    }
+
+    #[test]
+    fn test_wireformat_empty_vec_roundtrip()
+    {
+        let items: Vec<u8> = Vec::new();
+
+        let mut buf = Vec::new();
+        items.encode(&mut buf).unwrap();
+        assert_eq!(buf, b"0:,");
+
+        let mut cursor = Cursor::new(buf.as_slice());
+        assert_eq!(Vec::<u8>::decode(&mut cursor).unwrap(), items);
+    }
+
+    #[test]
+    fn test_wireformat_vec_rejects_truncated_trailing_item()
+    {
+        // One valid `u8` item (`5`) followed by a dangling, truncated partial
+        // item (`"1"` with no colon, payload, or comma) inside the outer frame.
+        let mut cursor = Cursor::new(b"5:1:\x05,1,".as_slice());
+        assert!(Vec::<u8>::decode(&mut cursor).is_err());
+    }
 }
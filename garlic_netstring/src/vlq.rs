@@ -0,0 +1,189 @@
+//! A binary variant of the netstring format with a VLQ length prefix.
+//!
+//! Instead of ASCII decimal digits, the length is encoded as a variable-length
+//! quantity: 7-bit groups, most-significant-first, with the high bit set on
+//! every byte but the last. This avoids per-digit base-10 parsing and shrinks
+//! headers for large payloads.
+//!
+//! There is no `:` separator between the length prefix and the payload,
+//! since the VLQ is self-delimiting and the payload follows immediately.
+//! The payload is still terminated with a `,`, for compatibility with the
+//! textual format's framing.
+
+use {
+    crate::Error,
+    std::io::{
+        self,
+        Read,
+        Write,
+    },
+};
+
+/// Encode a netstring with VLQ length-prefix framing.
+///
+/// See the [module documentation](self) for the format.
+pub fn encode<W>(w: &mut W, payload: &[u8]) -> io::Result<()>
+    where W: Write + ?Sized
+{
+    encode_len(w, payload.len() as u64)?;
+    w.write_all(payload)?;
+    w.write_all(b",")?;
+    Ok(())
+}
+
+/// Write `len` as a VLQ, without allocating a temporary buffer.
+fn encode_len<W>(w: &mut W, len: u64) -> io::Result<()>
+    where W: Write + ?Sized
+{
+    // A u64 needs at most 10 groups of 7 bits.
+    let mut groups = [0u8; 10];
+    let last = groups.len() - 1;
+    let mut i = last;
+    let mut n = len;
+    loop {
+        groups[i] = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            break;
+        }
+        i -= 1;
+    }
+
+    for &group in &groups[i .. last] {
+        w.write_all(&[group | 0x80])?;
+    }
+    w.write_all(&[groups[last]])
+}
+
+/// Decode a netstring with VLQ length-prefix framing.
+///
+/// See the [module documentation](self) for the format.
+pub fn decode<R, F>(r: &mut R, f: F, buf: &mut Vec<u8>) -> Result<u64, Error>
+    where R: Read, F: FnOnce(u64) -> bool
+{
+    let mut offset = 0u64;
+
+    // Read the length specified.
+    let len = decode_len(r, &mut offset)?;
+    if !f(len) {
+        return Err(Error::Length(len));
+    }
+
+    // Read the payload.
+    let nread = r.take(len).read_to_end(buf)?;
+    offset += nread as u64;
+    if nread as u64 != len {
+        return Err(Error::Incomplete);
+    }
+
+    // Read the terminating comma.
+    let mut commabuf = [0];
+    r.read_exact(&mut commabuf)?;
+    if commabuf[0] != b',' {
+        return Err(Error::Syntax{byte: commabuf[0], offset});
+    }
+
+    Ok(len)
+}
+
+/// The top 7 bits of a `u64`: if any of these are already set,
+/// shifting left by another 7-bit group would lose bits.
+const TOP_7_BITS: u64 = 0x7f << 57;
+
+fn decode_len<R>(r: &mut R, offset: &mut u64) -> Result<u64, Error>
+    where R: Read
+{
+    let mut len = 0u64;
+    let mut buf = [0];
+    loop {
+        if r.read(&mut buf)? == 0 {
+            return Err(Error::Incomplete);
+        }
+        let byte = buf[0];
+        let pos = *offset;
+        *offset += 1;
+
+        if len & TOP_7_BITS != 0 {
+            return Err(Error::Overflow{byte, offset: pos});
+        }
+        len = (len << 7) | (byte & 0x7f) as u64;
+
+        if byte & 0x80 == 0 {
+            return Ok(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        std::io::Cursor,
+    };
+
+    #[test]
+    fn test_roundtrip()
+    {
+        let examples: &[&[u8]] = &[b"", b"A", b"Hello, world!"];
+
+        for &payload in examples {
+            let mut encoded = Vec::new();
+            encode(&mut encoded, payload).unwrap();
+
+            let mut cursor = Cursor::new(encoded.as_slice());
+            let mut actual = Vec::new();
+            decode(&mut cursor, |_| true, &mut actual).unwrap();
+            assert_eq!(actual, payload);
+            assert_eq!(cursor.position(), encoded.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_encode_len_multibyte()
+    {
+        let mut buf = Vec::new();
+        encode_len(&mut buf, 300).unwrap();
+        // 300 = 0b1_0010_1100 = groups [0b0000010, 0b0101100]
+        assert_eq!(buf, [0b1000_0010, 0b0010_1100]);
+    }
+
+    #[test]
+    fn test_decode_incomplete()
+    {
+        let mut cursor = Cursor::new(b"\x80".as_slice());
+        let mut buf = Vec::new();
+        assert!(matches!(
+            decode(&mut cursor, |_| true, &mut buf),
+            Err(Error::Incomplete),
+        ));
+    }
+
+    #[test]
+    fn test_decode_overflow()
+    {
+        // 10 continuation bytes carrying 70 significant bits, which cannot
+        // fit in a u64.
+        let mut cursor = Cursor::new([0xFFu8; 10].as_slice());
+        let mut buf = Vec::new();
+        assert!(matches!(
+            decode(&mut cursor, |_| true, &mut buf),
+            Err(Error::Overflow{..}),
+        ));
+    }
+
+    #[test]
+    fn test_decode_overflow_does_not_wrap()
+    {
+        // Mathematically this prefix encodes 2^76 + 3, which does not fit in
+        // a u64; it must be rejected rather than silently wrapping to 3.
+        let mut input = vec![0xC0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x03];
+        input.extend_from_slice(b"XYZ,");
+        let mut cursor = Cursor::new(input.as_slice());
+        let mut buf = Vec::new();
+        assert!(matches!(
+            decode(&mut cursor, |_| true, &mut buf),
+            Err(Error::Overflow{..}),
+        ));
+    }
+}